@@ -1,10 +1,14 @@
 use futures::future::BoxFuture;
-use hyper::client::connect::HttpConnector;
+use hyper::client::connect::{Connect, HttpConnector};
+use hyper::header::{CONNECTION, UPGRADE};
 use hyper::service::Service;
-use hyper::{Body, Client, Request, Response};
+use hyper::{Body, Client, Request, Response, StatusCode};
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 use std::{
     sync::{Arc, Mutex},
     task::{Context, Poll},
@@ -19,35 +23,361 @@ use crate::Middlewares;
 // type BoxFut = Box<dyn Future<Output = Result<hyper::Response<Body>, hyper::Error>> + Send>;
 pub type State = Arc<Mutex<HashMap<(String, u64), serde_json::Value>>>;
 
-pub struct ProxyService {
-    client: Client<HttpConnector>,
+// Bounded response cache shared across every request on this `ProxyService`,
+// as opposed to `State` which is wiped at the start of each `call`.
+pub type Cache = Arc<Mutex<ResponseCache>>;
+
+const DEFAULT_CACHE_CAPACITY: usize = 128;
+
+// `State` key a middleware writes to during `before_request` to bound how
+// long the upstream call in `call` is allowed to run for this request.
+const TIMEOUT_STATE_KEY: &str = "__upstream_timeout_ms";
+
+// Index into `ResponseCache::nodes`. `NONE` stands in for a null link since
+// the list lives in a slab rather than being heap-allocated node by node.
+type NodeIndex = usize;
+const NONE: NodeIndex = usize::MAX;
+
+#[derive(Clone, Hash, Eq, PartialEq, Debug)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    // Built from the method, URI, and whichever request headers the caller
+    // considers part of the cache's vary-ation (e.g. `Accept-Encoding`).
+    pub fn new(req: &Request<Body>, vary_headers: &[hyper::header::HeaderName]) -> Self {
+        let mut key = format!("{} {}", req.method(), req.uri());
+        for name in vary_headers {
+            if let Some(value) = req.headers().get(name) {
+                key.push('\n');
+                key.push_str(name.as_str());
+                key.push(':');
+                key.push_str(value.to_str().unwrap_or(""));
+            }
+        }
+        CacheKey(key)
+    }
+}
+
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: hyper::HeaderMap,
+    pub body: hyper::body::Bytes,
+}
+
+impl From<&CachedResponse> for Response<Body> {
+    fn from(cached: &CachedResponse) -> Self {
+        let mut res = Response::new(Body::from(cached.body.clone()));
+        *res.status_mut() = cached.status;
+        *res.headers_mut() = cached.headers.clone();
+        res
+    }
+}
+
+struct CacheNode {
+    key: CacheKey,
+    value: CachedResponse,
+    prev: NodeIndex,
+    next: NodeIndex,
+}
+
+// LRU cache: a `HashMap` for O(1) lookup plus a slab-backed doubly linked
+// list for O(1) reordering/eviction. `head` holds the least-recently-used
+// entry, `tail` the most-recently-used one.
+pub struct ResponseCache {
+    capacity: usize,
+    index: HashMap<CacheKey, NodeIndex>,
+    nodes: Vec<CacheNode>,
+    free: Vec<NodeIndex>,
+    head: NodeIndex,
+    tail: NodeIndex,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize) -> Self {
+        ResponseCache {
+            capacity,
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: NONE,
+            tail: NONE,
+        }
+    }
+
+    pub fn get(&mut self, key: &CacheKey) -> Option<CachedResponse> {
+        let idx = *self.index.get(key)?;
+        self.detach(idx);
+        self.attach_as_mru(idx);
+        Some(self.nodes[idx].value.clone())
+    }
+
+    pub fn insert(&mut self, key: CacheKey, value: CachedResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(&idx) = self.index.get(&key) {
+            self.nodes[idx].value = value;
+            self.detach(idx);
+            self.attach_as_mru(idx);
+            return;
+        }
+
+        if self.index.len() >= self.capacity {
+            self.evict_lru();
+        }
+
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = CacheNode {
+                    key: key.clone(),
+                    value,
+                    prev: NONE,
+                    next: NONE,
+                };
+                idx
+            }
+            None => {
+                self.nodes.push(CacheNode {
+                    key: key.clone(),
+                    value,
+                    prev: NONE,
+                    next: NONE,
+                });
+                self.nodes.len() - 1
+            }
+        };
+
+        self.index.insert(key, idx);
+        self.attach_as_mru(idx);
+    }
+
+    fn evict_lru(&mut self) {
+        let idx = self.head;
+        if idx == NONE {
+            return;
+        }
+        self.detach(idx);
+        self.index.remove(&self.nodes[idx].key);
+        self.free.push(idx);
+    }
+
+    fn detach(&mut self, idx: NodeIndex) {
+        let (prev, next) = (self.nodes[idx].prev, self.nodes[idx].next);
+        if prev != NONE {
+            self.nodes[prev].next = next;
+        } else {
+            self.head = next;
+        }
+        if next != NONE {
+            self.nodes[next].prev = prev;
+        } else {
+            self.tail = prev;
+        }
+        self.nodes[idx].prev = NONE;
+        self.nodes[idx].next = NONE;
+    }
+
+    fn attach_as_mru(&mut self, idx: NodeIndex) {
+        self.nodes[idx].prev = self.tail;
+        self.nodes[idx].next = NONE;
+        if self.tail != NONE {
+            self.nodes[self.tail].next = idx;
+        }
+        self.tail = idx;
+        if self.head == NONE {
+            self.head = idx;
+        }
+    }
+}
+
+// A single upstream origin this `ProxyService` can forward to. `in_flight`
+// tracks requests currently being served by this backend so the
+// `LeastConnections` strategy can compare load across the pool.
+#[derive(Clone)]
+pub struct Backend {
+    pub addr: SocketAddr,
+    pub weight: u32,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Backend {
+    pub fn new(addr: SocketAddr) -> Self {
+        Backend::weighted(addr, 1)
+    }
+
+    pub fn weighted(addr: SocketAddr, weight: u32) -> Self {
+        Backend {
+            addr,
+            weight,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+pub type Backends = Arc<Vec<Backend>>;
+
+// A `ProxyService` with no backends has nowhere to send any request, and the
+// load balancer strategies either divide by zero (`RoundRobin`), sample an
+// empty range (`WeightedRandom`) or return an out-of-bounds index
+// (`LeastConnections`). Fail fast at construction instead of panicking on
+// the first request.
+fn validate_backends(backends: &Backends) {
+    assert!(
+        !backends.is_empty(),
+        "ProxyService requires at least one backend"
+    );
+}
+
+pub trait LoadBalancer: Send + Sync {
+    fn select(&self, backends: &[Backend], rng: &mut SmallRng) -> usize;
+}
+
+#[derive(Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl LoadBalancer for RoundRobin {
+    fn select(&self, backends: &[Backend], _rng: &mut SmallRng) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % backends.len()
+    }
+}
+
+pub struct WeightedRandom;
+
+impl LoadBalancer for WeightedRandom {
+    fn select(&self, backends: &[Backend], rng: &mut SmallRng) -> usize {
+        let total_weight: u32 = backends.iter().map(|backend| backend.weight.max(1)).sum();
+        let mut pick = rng.gen_range(0..total_weight);
+        for (index, backend) in backends.iter().enumerate() {
+            let weight = backend.weight.max(1);
+            if pick < weight {
+                return index;
+            }
+            pick -= weight;
+        }
+        backends.len() - 1
+    }
+}
+
+pub struct LeastConnections;
+
+impl LoadBalancer for LeastConnections {
+    fn select(&self, backends: &[Backend], _rng: &mut SmallRng) -> usize {
+        backends
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, backend)| backend.in_flight.load(Ordering::Relaxed))
+            .map(|(index, _)| index)
+            .unwrap_or(0)
+    }
+}
+
+// Generic over the connector so the proxy can dial plaintext HTTP/1
+// upstreams (the default `HttpConnector`) as well as TLS/h2 ones (e.g.
+// `hyper_tls::HttpsConnector`).
+pub struct ProxyService<C = HttpConnector> {
+    client: Client<C>,
     middlewares: Middlewares,
     state: State,
+    cache: Cache,
     remote_addr: SocketAddr,
+    backends: Backends,
+    load_balancer: Arc<dyn LoadBalancer>,
     rng: SmallRng,
+    in_flight: Arc<AtomicUsize>,
+    shutdown_notify: Arc<tokio::sync::Notify>,
+    shutting_down: Arc<AtomicBool>,
+    // Scheme to dial backends with (`http` for the plain `HttpConnector`,
+    // `https` for `hyper_tls::HttpsConnector`). Rewriting a request's
+    // authority to point at a backend must preserve this, or an
+    // `HttpsConnector` silently falls back to plaintext TCP.
+    backend_scheme: hyper::http::uri::Scheme,
+}
+
+// Wraps any error this service can produce so graceful shutdown can report
+// "not accepting new work" without being tied to `hyper::Error` specifically.
+pub type ServiceError = Box<dyn std::error::Error + Send + Sync>;
+
+// Normally drops alongside the boxed future `call` returns, so the in-flight
+// count is accurate regardless of which path through the handler it took.
+// For upgrade requests it is instead moved into the spawned tunnel task, so
+// the count stays elevated for the tunnel's actual lifetime rather than just
+// until the upgrade handshake was spliced.
+struct InFlightGuard {
+    in_flight: Arc<AtomicUsize>,
+    shutdown_notify: Arc<tokio::sync::Notify>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shutdown_notify.notify_waiters();
+        }
+    }
+}
+
+// Protocol/connector knobs for `ProxyService::with_connector`.
+pub struct ConnectorOptions {
+    pub http2_only: bool,
+    pub http2_adaptive_window: bool,
+    pub cache_capacity: usize,
+    pub load_balancer: Arc<dyn LoadBalancer>,
+    // Scheme backends are dialed with. Must match the connector: `https`
+    // for `hyper_tls::HttpsConnector`, `http` otherwise.
+    pub backend_scheme: hyper::http::uri::Scheme,
+}
+
+impl Default for ConnectorOptions {
+    fn default() -> Self {
+        ConnectorOptions {
+            http2_only: false,
+            http2_adaptive_window: true,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            load_balancer: Arc::new(RoundRobin::default()),
+            backend_scheme: hyper::http::uri::Scheme::HTTP,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct ServiceContext {
     pub remote_addr: SocketAddr,
+    pub backend_addr: SocketAddr,
     pub req_id: u64,
 }
 
-impl Service<Request<hyper::Body>> for ProxyService {
+impl<C> Service<Request<hyper::Body>> for ProxyService<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
     type Response = Response<hyper::Body>;
-    type Error = hyper::Error;
+    type Error = ServiceError;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.shutting_down.load(Ordering::Acquire) {
+            return Poll::Ready(Err("proxy is shutting down".into()));
+        }
+
         match self.client.poll_ready(cx) {
             Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
-            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
             Poll::Pending => Poll::Pending,
         }
     }
 
     fn call(&mut self, req: Request<hyper::Body>) -> Self::Future {
         self.clear_state();
+
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let in_flight_guard = InFlightGuard {
+            in_flight: Arc::clone(&self.in_flight),
+            shutdown_notify: Arc::clone(&self.shutdown_notify),
+        };
+
         let (parts, body) = req.into_parts();
         let mut req = Request::from_parts(parts, body);
 
@@ -61,12 +391,21 @@ impl Service<Request<hyper::Body>> for ProxyService {
         let state_success = Arc::clone(&self.state);
         let state_after_success = Arc::clone(&self.state);
         let state_after_failure = Arc::clone(&self.state);
+        let cache_after_success = Arc::clone(&self.cache);
+        let cache_after_failure = Arc::clone(&self.cache);
 
         let req_id = self.rng.next_u64();
 
+        let backend_index = self.load_balancer.select(&self.backends, &mut self.rng);
+        let backend = &self.backends[backend_index];
+        let backend_addr = backend.addr;
+        let backend_in_flight = Arc::clone(&backend.in_flight);
+        let backend_scheme = self.backend_scheme.clone();
+
         let context = ServiceContext {
             req_id,
             remote_addr: self.remote_addr,
+            backend_addr,
         };
 
         let mut before_res: Option<Response<Body>> = None;
@@ -74,11 +413,14 @@ impl Service<Request<hyper::Body>> for ProxyService {
         let middlewares = self.middlewares.clone();
         let client = self.client.clone();
         let state = self.state.clone();
+        let cache = self.cache.clone();
 
         Box::pin(async move {
             for mw in middlewares.lock().await.iter_mut() {
-                // Run all middlewares->before_request
-                if let Some(res) = match mw.before_request(&mut req, &context, &state) {
+                // Run all middlewares->before_request. A cache-hit middleware
+                // can short-circuit here with `RespondWith`, same as any
+                // other before_request responder.
+                if let Some(res) = match mw.before_request(&mut req, &context, &state, &cache) {
                     Err(err) => Some(Response::from(err)),
                     Ok(RespondWith(response)) => Some(response),
                     Ok(Next) => None,
@@ -89,7 +431,10 @@ impl Service<Request<hyper::Body>> for ProxyService {
                 }
 
                 // Run all middlewares->before_request_async
-                if let Some(res) = match mw.before_request_async(&mut req, &context, &state).await {
+                if let Some(res) = match mw
+                    .before_request_async(&mut req, &context, &state, &cache)
+                    .await
+                {
                     Err(err) => Some(Response::from(err)),
                     Ok(RespondWith(response)) => Some(response),
                     Ok(Next) => None,
@@ -101,11 +446,58 @@ impl Service<Request<hyper::Body>> for ProxyService {
             }
 
             if let Some(res) = before_res {
-                return Ok(Self::early_response(&middlewares, &context, res, &state).await);
+                return Ok(Self::early_response(&middlewares, &context, res, &state, &cache).await);
             }
 
-            let maybe_res = match client.request(req).await {
-                Err(err) => {
+            Self::set_backend_authority(&mut req, backend_addr, backend_scheme);
+
+            if Self::is_upgrade_request(&req) {
+                // Hand the in-flight guard into `handle_upgrade` itself: for
+                // an upgrade, this call's future resolves as soon as the
+                // tunnel is spliced and spawned, long before the tunnel
+                // actually closes, so the guard must outlive this `await`
+                // and ride along with the spawned copy task instead.
+                return Ok(Self::handle_upgrade(
+                    req,
+                    middlewares,
+                    client,
+                    context,
+                    state,
+                    backend_in_flight,
+                    in_flight_guard,
+                )
+                .await);
+            }
+
+            let _in_flight_guard = in_flight_guard;
+
+            backend_in_flight.fetch_add(1, Ordering::Relaxed);
+
+            // A middleware may have bounded this request's upstream call
+            // during `before_request` by writing a timeout in milliseconds
+            // into `state`.
+            // `select!` drops (and so cancels) whichever branch loses the
+            // race, so the upstream call needs no explicit abort handle.
+            let upstream_result = match Self::upstream_timeout(&state, req_id) {
+                Some(timeout) => {
+                    tokio::select! {
+                        result = client.request(req) => Some(result),
+                        _ = tokio::time::sleep(timeout) => {
+                            error!("Upstream call for req {} timed out after {:?}", req_id, timeout);
+                            None
+                        }
+                    }
+                }
+                None => Some(client.request(req).await),
+            };
+
+            backend_in_flight.fetch_sub(1, Ordering::Relaxed);
+
+            let maybe_res = match upstream_result {
+                // The timer won the race: skip request_success/request_failure
+                // entirely and synthesize the timeout response.
+                None => Ok(Self::gateway_timeout()),
+                Some(Err(err)) => {
                     for mw in mws_failure.lock().await.iter_mut() {
                         // TODO: think about graceful handling
                         if let Err(err) = mw.request_failure(&err, &context, &state_failure) {
@@ -114,7 +506,7 @@ impl Service<Request<hyper::Body>> for ProxyService {
                     }
                     Err(err)
                 }
-                Ok(mut res) => {
+                Some(Ok(mut res)) => {
                     for mw in mws_success.lock().await.iter_mut() {
                         match mw.request_success(&mut res, &context, &state_success) {
                             Err(err) => res = Response::from(err),
@@ -129,14 +521,24 @@ impl Service<Request<hyper::Body>> for ProxyService {
             match maybe_res {
                 Ok(mut res) => {
                     for mw in mws_after_failure.lock().await.iter_mut() {
-                        match mw.after_request(Some(&mut res), &context, &state_after_failure) {
+                        match mw.after_request(
+                            Some(&mut res),
+                            &context,
+                            &state_after_failure,
+                            &cache_after_failure,
+                        ) {
                             Err(err) => res = Response::from(err),
                             Ok(RespondWith(response)) => res = response,
                             Ok(Next) => (),
                         }
 
                         match mw
-                            .after_request_async(Some(&mut res), &context, &state_after_failure)
+                            .after_request_async(
+                                Some(&mut res),
+                                &context,
+                                &state_after_failure,
+                                &cache_after_failure,
+                            )
                             .await
                         {
                             Err(err) => res = Response::from(err),
@@ -149,7 +551,12 @@ impl Service<Request<hyper::Body>> for ProxyService {
                 Err(err) => {
                     let mut res = Err(err);
                     for mw in mws_after_success.lock().await.iter_mut() {
-                        match mw.after_request(None, &context, &state_after_success) {
+                        match mw.after_request(
+                            None,
+                            &context,
+                            &state_after_success,
+                            &cache_after_success,
+                        ) {
                             Err(err) => res = Ok(Response::from(err)),
                             Ok(RespondWith(response)) => res = Ok(response),
                             Ok(Next) => (),
@@ -158,19 +565,150 @@ impl Service<Request<hyper::Body>> for ProxyService {
                     res
                 }
             }
+            .map_err(ServiceError::from)
         })
     }
 }
 
-impl ProxyService {
+impl<C> ProxyService<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    // A request is only eligible for tunneling when it carries both
+    // `Connection: Upgrade` and a concrete `Upgrade` token (e.g.
+    // `websocket`). Whether the upstream accepts is decided later, by its
+    // `101` response.
+    fn is_upgrade_request(req: &Request<Body>) -> bool {
+        let has_connection_upgrade = req
+            .headers()
+            .get(CONNECTION)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_ascii_lowercase().contains("upgrade"))
+            .unwrap_or(false);
+
+        has_connection_upgrade && req.headers().get(UPGRADE).is_some()
+    }
+
+    // Forwards an Upgrade request to the upstream and, once it replies with
+    // `101 Switching Protocols`, splices the client<->proxy and
+    // proxy<->upstream byte streams together so the tunnel is fully
+    // transparent (WebSockets and friends).
+    async fn handle_upgrade(
+        mut req: Request<Body>,
+        middlewares: Middlewares,
+        client: Client<C>,
+        context: ServiceContext,
+        state: State,
+        backend_in_flight: Arc<AtomicUsize>,
+        in_flight_guard: InFlightGuard,
+    ) -> Response<Body> {
+        for mw in middlewares.lock().await.iter_mut() {
+            if let Err(err) = mw.on_upgrade(&req, &context, &state) {
+                error!("on_upgrade rejected the handshake: {:?}", &err);
+                return Response::from(err);
+            }
+        }
+
+        let client_upgrade = hyper::upgrade::on(&mut req);
+
+        backend_in_flight.fetch_add(1, Ordering::Relaxed);
+        let upstream_res = client.request(req).await;
+        backend_in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        let upstream_res = match upstream_res {
+            Ok(res) => res,
+            Err(err) => {
+                error!("Upgrade request to upstream failed: {:?}", &err);
+                return Self::bad_gateway();
+            }
+        };
+
+        if upstream_res.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return upstream_res;
+        }
+
+        let (parts, body) = upstream_res.into_parts();
+        let mut upstream_res = Response::from_parts(parts, body);
+        let upstream_upgrade = hyper::upgrade::on(&mut upstream_res);
+
+        tokio::spawn(async move {
+            // Keep the request counted as in-flight for as long as the
+            // tunnel itself is open, not just until it was spliced.
+            let _in_flight_guard = in_flight_guard;
+
+            match futures::try_join!(client_upgrade, upstream_upgrade) {
+                Ok((mut client_stream, mut upstream_stream)) => {
+                    if let Err(err) =
+                        tokio::io::copy_bidirectional(&mut client_stream, &mut upstream_stream)
+                            .await
+                    {
+                        error!("Upgrade tunnel closed with an error: {:?}", err);
+                    }
+                }
+                Err(err) => error!("Upgrade handshake failed: {:?}", err),
+            }
+        });
+
+        upstream_res
+    }
+
+    fn bad_gateway() -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::BAD_GATEWAY)
+            .body(Body::empty())
+            .expect("building a static response should never fail")
+    }
+
+    // Rewrites the outgoing request's authority to point at the backend the
+    // load balancer selected for this call, leaving path/query untouched.
+    // The scheme is forced to whatever this service dials backends with
+    // (`backend_scheme`), not whatever scheme the inbound URI happened to
+    // carry (usually none) — otherwise an `HttpsConnector` would silently
+    // fall back to plaintext TCP.
+    fn set_backend_authority(
+        req: &mut Request<Body>,
+        backend_addr: SocketAddr,
+        backend_scheme: hyper::http::uri::Scheme,
+    ) {
+        let mut parts = req.uri().clone().into_parts();
+        parts.scheme = Some(backend_scheme);
+        parts.authority =
+            hyper::http::uri::Authority::try_from(backend_addr.to_string().as_str()).ok();
+        if let Ok(uri) = hyper::Uri::from_parts(parts) {
+            *req.uri_mut() = uri;
+        }
+    }
+
+    fn gateway_timeout() -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::GATEWAY_TIMEOUT)
+            .body(Body::empty())
+            .expect("building a static response should never fail")
+    }
+
+    // Reads the per-request timeout (in milliseconds, under
+    // `TIMEOUT_STATE_KEY` and this request's id) a middleware may have set in
+    // `state` during `before_request`.
+    fn upstream_timeout(state: &State, req_id: u64) -> Option<Duration> {
+        let state = match state.lock() {
+            Ok(state) => state,
+            Err(_) => return None,
+        };
+        state
+            .get(&(TIMEOUT_STATE_KEY.to_string(), req_id))
+            .and_then(|value| value.as_u64())
+            .map(Duration::from_millis)
+    }
+
     async fn early_response(
         middlewares: &Middlewares,
         context: &ServiceContext,
         mut res: Response<Body>,
         state: &State,
+        cache: &Cache,
     ) -> Response<Body> {
         for mw in middlewares.lock().await.iter_mut() {
-            match mw.after_request(Some(&mut res), context, state) {
+            match mw.after_request(Some(&mut res), context, state, cache) {
                 Err(err) => res = Response::from(err),
                 Ok(RespondWith(response)) => res = response,
                 Ok(Next) => (),
@@ -191,13 +729,284 @@ impl ProxyService {
         }
     }
 
-    pub fn new(middlewares: Middlewares, remote_addr: SocketAddr) -> Self {
+    // Builds a `ProxyService` around an arbitrary connector (e.g. an
+    // `HttpsConnector` for TLS/h2 upstreams), configured via `options`.
+    pub fn with_connector(
+        middlewares: Middlewares,
+        remote_addr: SocketAddr,
+        backends: Backends,
+        connector: C,
+        options: ConnectorOptions,
+    ) -> Self {
+        validate_backends(&backends);
+
+        let client = Client::builder()
+            .http2_only(options.http2_only)
+            .http2_adaptive_window(options.http2_adaptive_window)
+            .build(connector);
+
         ProxyService {
-            client: Client::new(),
+            client,
             state: Arc::new(Mutex::new(HashMap::new())),
+            cache: Arc::new(Mutex::new(ResponseCache::new(options.cache_capacity))),
             rng: SmallRng::from_entropy(),
             remote_addr,
+            backends,
+            load_balancer: options.load_balancer,
             middlewares,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            shutdown_notify: Arc::new(tokio::sync::Notify::new()),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            backend_scheme: options.backend_scheme,
         }
     }
+
+    // Stops accepting new work (`poll_ready` starts failing) and returns a
+    // future that resolves once every in-flight request — upgrade tunnels
+    // included — has finished.
+    pub fn shutdown(&self) -> impl std::future::Future<Output = ()> + 'static {
+        self.shutting_down.store(true, Ordering::Release);
+        let in_flight = Arc::clone(&self.in_flight);
+        let shutdown_notify = Arc::clone(&self.shutdown_notify);
+        async move {
+            loop {
+                let notified = shutdown_notify.notified();
+                tokio::pin!(notified);
+                // Arm the `Notified` as a waiter *before* checking
+                // `in_flight`, so a guard dropping (and calling
+                // `notify_waiters()`) between the check and the `.await`
+                // below can't be missed — `Notified` only registers itself
+                // on first poll, and a plain `.await` after the check would
+                // leave a lost-wakeup window.
+                notified.as_mut().enable();
+
+                if in_flight.load(Ordering::Acquire) == 0 {
+                    return;
+                }
+
+                notified.await;
+            }
+        }
+    }
+}
+
+impl ProxyService<HttpConnector> {
+    pub fn new(middlewares: Middlewares, remote_addr: SocketAddr, backends: Backends) -> Self {
+        Self::with_cache_capacity(middlewares, remote_addr, backends, DEFAULT_CACHE_CAPACITY)
+    }
+
+    pub fn with_cache_capacity(
+        middlewares: Middlewares,
+        remote_addr: SocketAddr,
+        backends: Backends,
+        cache_capacity: usize,
+    ) -> Self {
+        Self::with_load_balancer(
+            middlewares,
+            remote_addr,
+            backends,
+            cache_capacity,
+            Arc::new(RoundRobin::default()),
+        )
+    }
+
+    pub fn with_load_balancer(
+        middlewares: Middlewares,
+        remote_addr: SocketAddr,
+        backends: Backends,
+        cache_capacity: usize,
+        load_balancer: Arc<dyn LoadBalancer>,
+    ) -> Self {
+        ProxyService::with_connector(
+            middlewares,
+            remote_addr,
+            backends,
+            HttpConnector::new(),
+            ConnectorOptions {
+                cache_capacity,
+                load_balancer,
+                ..ConnectorOptions::default()
+            },
+        )
+    }
+}
+
+impl ProxyService<hyper_tls::HttpsConnector<HttpConnector>> {
+    // Convenience constructor for TLS upstreams. The client is built with
+    // `http2_only`, so it always speaks HTTP/2 and never falls back to HTTP/1.
+    pub fn with_tls(middlewares: Middlewares, remote_addr: SocketAddr, backends: Backends) -> Self {
+        Self::with_connector(
+            middlewares,
+            remote_addr,
+            backends,
+            hyper_tls::HttpsConnector::new(),
+            ConnectorOptions {
+                http2_only: true,
+                backend_scheme: hyper::http::uri::Scheme::HTTPS,
+                ..ConnectorOptions::default()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached(body: &str) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: hyper::HeaderMap::new(),
+            body: hyper::body::Bytes::from(body.to_string()),
+        }
+    }
+
+    fn key(id: &str) -> CacheKey {
+        CacheKey(id.to_string())
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry_first() {
+        let mut cache = ResponseCache::new(2);
+        cache.insert(key("a"), cached("a"));
+        cache.insert(key("b"), cached("b"));
+
+        // Capacity is full: this evicts "a", the least recently used entry.
+        cache.insert(key("c"), cached("c"));
+
+        assert!(cache.get(&key("a")).is_none());
+        assert!(cache.get(&key("b")).is_some());
+        assert!(cache.get(&key("c")).is_some());
+    }
+
+    #[test]
+    fn get_promotes_an_entry_to_most_recently_used() {
+        let mut cache = ResponseCache::new(2);
+        cache.insert(key("a"), cached("a"));
+        cache.insert(key("b"), cached("b"));
+
+        // Touching "a" makes "b" the least recently used entry instead.
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("c"), cached("c"));
+
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("c")).is_some());
+    }
+
+    #[test]
+    fn insert_is_a_no_op_for_a_zero_capacity_cache() {
+        let mut cache = ResponseCache::new(0);
+        cache.insert(key("a"), cached("a"));
+
+        assert!(cache.get(&key("a")).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one backend")]
+    fn rejects_an_empty_backend_list() {
+        validate_backends(&Arc::new(Vec::new()));
+    }
+
+    fn addr_at(port: u16) -> SocketAddr {
+        format!("127.0.0.1:{}", port).parse().unwrap()
+    }
+
+    fn backend_at(port: u16) -> Backend {
+        Backend::new(addr_at(port))
+    }
+
+    #[test]
+    fn round_robin_wraps_around_all_backends() {
+        let backends = vec![backend_at(1), backend_at(2), backend_at(3)];
+        let lb = RoundRobin::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let picks: Vec<usize> = (0..6).map(|_| lb.select(&backends, &mut rng)).collect();
+
+        assert_eq!(picks, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn weighted_random_favors_the_more_heavily_weighted_backend() {
+        let backends = vec![
+            Backend::weighted(addr_at(1), 1),
+            Backend::weighted(addr_at(2), 9),
+        ];
+        let lb = WeightedRandom;
+        let mut rng = SmallRng::seed_from_u64(42);
+
+        let mut counts = [0usize; 2];
+        for _ in 0..1000 {
+            counts[lb.select(&backends, &mut rng)] += 1;
+        }
+
+        // Weight ratio is 1:9; allow generous slack and just assert the
+        // heavier backend clearly dominates rather than pin an exact count.
+        assert!(counts[1] > counts[0] * 4, "counts: {:?}", counts);
+    }
+
+    #[test]
+    fn least_connections_picks_the_backend_with_fewest_in_flight_requests() {
+        let low_a = backend_at(1);
+        let low_b = backend_at(2);
+        let high = backend_at(3);
+        low_a.in_flight.store(2, Ordering::Relaxed);
+        low_b.in_flight.store(2, Ordering::Relaxed);
+        high.in_flight.store(5, Ordering::Relaxed);
+        let backends = vec![high, low_a, low_b];
+        let lb = LeastConnections;
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // Ties are broken in favor of the lowest index among the minimums.
+        assert_eq!(lb.select(&backends, &mut rng), 1);
+    }
+
+    #[test]
+    fn is_upgrade_request_requires_both_connection_and_upgrade_headers() {
+        let req = Request::builder()
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .body(Body::empty())
+            .unwrap();
+        assert!(ProxyService::<HttpConnector>::is_upgrade_request(&req));
+
+        let no_headers = Request::builder().body(Body::empty()).unwrap();
+        assert!(!ProxyService::<HttpConnector>::is_upgrade_request(
+            &no_headers
+        ));
+
+        let wrong_connection = Request::builder()
+            .header(CONNECTION, "keep-alive")
+            .header(UPGRADE, "websocket")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!ProxyService::<HttpConnector>::is_upgrade_request(
+            &wrong_connection
+        ));
+    }
+
+    #[test]
+    fn set_backend_authority_rewrites_scheme_and_authority_but_keeps_path_and_query() {
+        let mut req = Request::builder()
+            .uri("/foo?bar=1")
+            .body(Body::empty())
+            .unwrap();
+
+        ProxyService::<HttpConnector>::set_backend_authority(
+            &mut req,
+            addr_at(9000),
+            hyper::http::uri::Scheme::HTTPS,
+        );
+
+        assert_eq!(req.uri().scheme_str(), Some("https"));
+        assert_eq!(
+            req.uri().authority().map(|a| a.as_str()),
+            Some("127.0.0.1:9000")
+        );
+        assert_eq!(
+            req.uri().path_and_query().map(|pq| pq.as_str()),
+            Some("/foo?bar=1")
+        );
+    }
 }